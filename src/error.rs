@@ -0,0 +1,104 @@
+//! Structured errors for stats collection.
+
+use pgrx::prelude::*;
+
+#[derive(Debug)]
+pub(crate) enum SweepError {
+    /// The SPI call itself failed (bad SQL, permissions, out of memory, …).
+    Query {
+        query: &'static str,
+        column: &'static str,
+        source: pgrx::spi::Error,
+    },
+    /// The query succeeded but returned no row, or the named column was
+    /// `NULL`, and no `track_*` GUC explains why.
+    MissingValue {
+        query: &'static str,
+        column: &'static str,
+    },
+    /// The column is legitimately unmeasured because a `track_*` setting is
+    /// off, as opposed to a real collection failure.
+    TrackingDisabled {
+        guc: &'static str,
+        column: &'static str,
+    },
+    /// There isn't yet a snapshot old enough to bracket a delta against.
+    InsufficientHistory { target: String },
+}
+
+impl std::fmt::Display for SweepError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SweepError::Query {
+                query,
+                column,
+                source,
+            } => write!(
+                f,
+                "pg_sweep: failed to read column \"{column}\" from `{query}`: {source}"
+            ),
+            SweepError::MissingValue { query, column } => write!(
+                f,
+                "pg_sweep: query `{query}` returned no value for column \"{column}\""
+            ),
+            SweepError::TrackingDisabled { guc, column } => write!(
+                f,
+                "pg_sweep: column \"{column}\" is unavailable because `{guc}` is off"
+            ),
+            SweepError::InsufficientHistory { target } => write!(
+                f,
+                "pg_sweep: no snapshot of \"{target}\" old enough to compute a delta yet"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SweepError {}
+
+/// Reads and type-checks a single column off an already-fetched row,
+/// attaching the query and column name to any failure.
+pub(crate) fn get_column<T: FromDatum + IntoDatum>(
+    row: &SpiHeapTupleData,
+    query: &'static str,
+    column: &'static str,
+) -> Result<T, SweepError> {
+    row.get_by_name::<T>(column)
+        .map_err(|source| SweepError::Query {
+            query,
+            column,
+            source,
+        })?
+        .ok_or(SweepError::MissingValue { query, column })
+}
+
+/// Like [`get_column`], but a `NULL` is a legitimate value (e.g. a table
+/// that has never been autovacuumed) rather than a failure.
+pub(crate) fn get_optional_column<T: FromDatum + IntoDatum>(
+    row: &SpiHeapTupleData,
+    query: &'static str,
+    column: &'static str,
+) -> Result<Option<T>, SweepError> {
+    row.get_by_name::<T>(column).map_err(|source| SweepError::Query {
+        query,
+        column,
+        source,
+    })
+}
+
+/// Whether a boolean `track_*` GUC is currently on.
+pub(crate) fn track_enabled(client: &SpiClient<'_>, guc: &'static str) -> Result<bool, SweepError> {
+    const QUERY: &str = "SELECT current_setting($1)::boolean AS enabled";
+
+    let mut rows = client.select(
+        QUERY,
+        None,
+        Some(vec![(PgBuiltInOids::TEXTOID.oid(), guc.into_datum())]),
+    );
+
+    let row = rows.next().ok_or(SweepError::MissingValue {
+        query: QUERY,
+        column: "enabled",
+    })?;
+
+    get_column(&row, QUERY, "enabled")
+}
@@ -0,0 +1,268 @@
+//! Snapshot persistence and delta/rate computation.
+
+use crate::error::{get_column, SweepError};
+use crate::{DatabaseStats, TableStats};
+use pgrx::prelude::*;
+use serde::de::DeserializeOwned;
+
+extension_sql!(
+    r#"
+    CREATE TABLE pg_sweep_stats_history (
+        id bigserial PRIMARY KEY,
+        captured_at timestamptz NOT NULL DEFAULT clock_timestamp(),
+        target text NOT NULL,
+        stats jsonb NOT NULL
+    );
+
+    CREATE INDEX pg_sweep_stats_history_target_captured_at_idx
+        ON pg_sweep_stats_history (target, captured_at DESC);
+    "#,
+    name = "create_pg_sweep_stats_history",
+);
+
+/// Which series a snapshot belongs to: the single database-wide series, or
+/// one series per `schema.table`.
+pub(crate) enum Target<'a> {
+    Database,
+    Table(&'a str),
+}
+
+impl Target<'_> {
+    fn as_str(&self) -> &str {
+        match self {
+            Target::Database => "database",
+            Target::Table(key) => key,
+        }
+    }
+}
+
+pub(crate) fn record_snapshot<T: serde::Serialize>(
+    client: &SpiClient<'_>,
+    target: Target,
+    stats: &T,
+) {
+    let payload = JsonB(serde_json::to_value(stats).unwrap());
+    client.update(
+        "INSERT INTO pg_sweep_stats_history (target, stats) VALUES ($1, $2)",
+        None,
+        Some(vec![
+            (PgBuiltInOids::TEXTOID.oid(), target.as_str().into_datum()),
+            (PgBuiltInOids::JSONBOID.oid(), payload.into_datum()),
+        ]),
+    );
+}
+
+const BRACKETING_QUERY: &str = "WITH latest AS ( \
+         SELECT stats, captured_at FROM pg_sweep_stats_history \
+         WHERE target = $1 \
+         ORDER BY captured_at DESC LIMIT 1 \
+     ), baseline AS ( \
+         SELECT stats, captured_at FROM pg_sweep_stats_history \
+         WHERE target = $1 AND captured_at <= (SELECT captured_at FROM latest) - $2::interval \
+         ORDER BY captured_at DESC LIMIT 1 \
+     ) \
+     SELECT latest.stats AS latest_stats, baseline.stats AS baseline_stats, \
+            EXTRACT(EPOCH FROM (latest.captured_at - baseline.captured_at)) AS elapsed_secs \
+     FROM latest, baseline";
+
+/// The newest snapshot and the newest snapshot at least `interval` older,
+/// deserialized back into `T`, plus how many seconds actually separate them
+/// (which may be more than `interval` if collection is infrequent).
+fn bracketing_snapshots<T: DeserializeOwned>(
+    client: &SpiClient<'_>,
+    target: &str,
+    interval: &str,
+) -> Result<(T, T, f64), SweepError> {
+    let mut rows = client.select(
+        BRACKETING_QUERY,
+        None,
+        Some(vec![
+            (PgBuiltInOids::TEXTOID.oid(), target.into_datum()),
+            (PgBuiltInOids::TEXTOID.oid(), interval.into_datum()),
+        ]),
+    );
+
+    let row = rows.next().ok_or_else(|| SweepError::InsufficientHistory {
+        target: target.to_string(),
+    })?;
+
+    let latest: JsonB = get_column(&row, BRACKETING_QUERY, "latest_stats")?;
+    let baseline: JsonB = get_column(&row, BRACKETING_QUERY, "baseline_stats")?;
+    let elapsed_secs: f64 = get_column(&row, BRACKETING_QUERY, "elapsed_secs")?;
+
+    Ok((
+        serde_json::from_value(latest.0).unwrap(),
+        serde_json::from_value(baseline.0).unwrap(),
+        elapsed_secs,
+    ))
+}
+
+/// Delta of a monotonic counter across two snapshots. A counter reset (the
+/// newer value being smaller, e.g. after `pg_stat_reset()`) is reported as
+/// the raw newer value instead of going negative.
+fn counter_delta(newer: i64, older: i64) -> i64 {
+    if newer >= older {
+        newer - older
+    } else {
+        newer
+    }
+}
+
+fn rate(delta: i64, elapsed_secs: f64) -> f64 {
+    if elapsed_secs <= 0.0 {
+        0.0
+    } else {
+        delta as f64 / elapsed_secs
+    }
+}
+
+fn ratio(numerator: i64, denominator: i64) -> f64 {
+    if denominator == 0 {
+        0.0
+    } else {
+        numerator as f64 / denominator as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{counter_delta, rate, ratio};
+
+    #[test]
+    fn counter_delta_normal() {
+        assert_eq!(counter_delta(150, 100), 50);
+    }
+
+    #[test]
+    fn counter_delta_reset_returns_newer_value() {
+        assert_eq!(counter_delta(10, 100), 10);
+    }
+
+    #[test]
+    fn rate_normal() {
+        assert_eq!(rate(100, 10.0), 10.0);
+    }
+
+    #[test]
+    fn rate_zero_elapsed_is_zero() {
+        assert_eq!(rate(100, 0.0), 0.0);
+    }
+
+    #[test]
+    fn rate_negative_elapsed_is_zero() {
+        assert_eq!(rate(100, -5.0), 0.0);
+    }
+
+    #[test]
+    fn ratio_normal() {
+        assert_eq!(ratio(1, 4), 0.25);
+    }
+
+    #[test]
+    fn ratio_zero_denominator_is_zero() {
+        assert_eq!(ratio(5, 0), 0.0);
+    }
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct DatabaseStatsDelta {
+    elapsed_secs: f64,
+    commits: i64,
+    rollbacks: i64,
+    blocks_read: i64,
+    blocks_hit: i64,
+    tuples_returned: i64,
+    tuples_fetched: i64,
+    tuples_inserted: i64,
+    tuples_updated: i64,
+    tuples_deleted: i64,
+    temp_files: i64,
+    temp_bytes: i64,
+    deadlocks: i64,
+    commits_per_sec: f64,
+    tuples_written_per_sec: f64,
+    cache_hit_ratio: f64,
+}
+
+#[pg_extern]
+fn database_stats_delta(interval: String) -> Result<Json<DatabaseStatsDelta>, SweepError> {
+    Spi::connect(|client| {
+        let (latest, baseline, elapsed_secs): (DatabaseStats, DatabaseStats, f64) =
+            bracketing_snapshots(&client, "database", &interval)?;
+
+        let commits = counter_delta(latest.commits, baseline.commits);
+        let rollbacks = counter_delta(latest.rollbacks, baseline.rollbacks);
+        let blocks_read = counter_delta(latest.blocks_read, baseline.blocks_read);
+        let blocks_hit = counter_delta(latest.blocks_hit, baseline.blocks_hit);
+        let tuples_inserted = counter_delta(latest.tuples_inserted, baseline.tuples_inserted);
+        let tuples_updated = counter_delta(latest.tuples_updated, baseline.tuples_updated);
+        let tuples_deleted = counter_delta(latest.tuples_deleted, baseline.tuples_deleted);
+
+        Ok(Json(DatabaseStatsDelta {
+            elapsed_secs,
+            commits,
+            rollbacks,
+            blocks_read,
+            blocks_hit,
+            tuples_returned: counter_delta(latest.tuples_returned, baseline.tuples_returned),
+            tuples_fetched: counter_delta(latest.tuples_fetched, baseline.tuples_fetched),
+            tuples_inserted,
+            tuples_updated,
+            tuples_deleted,
+            temp_files: counter_delta(latest.temp_files, baseline.temp_files),
+            temp_bytes: counter_delta(latest.temp_bytes, baseline.temp_bytes),
+            deadlocks: counter_delta(latest.deadlocks, baseline.deadlocks),
+            commits_per_sec: rate(commits, elapsed_secs),
+            tuples_written_per_sec: rate(
+                tuples_inserted + tuples_updated + tuples_deleted,
+                elapsed_secs,
+            ),
+            cache_hit_ratio: ratio(blocks_hit, blocks_hit + blocks_read),
+        }))
+    })
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct TableStatsDelta {
+    elapsed_secs: f64,
+    sequential_scans: i64,
+    index_scans: i64,
+    rows_inserted: i64,
+    rows_updated: i64,
+    rows_deleted: i64,
+    dead_rows_growth: i64,
+    dead_rows_growth_per_sec: f64,
+    index_vs_seqscan_ratio: f64,
+    cache_hit_ratio: f64,
+}
+
+/// `table` is the `schema.table` key as returned by `collect_table_stats`.
+#[pg_extern]
+fn table_stats_delta(
+    table: String,
+    interval: String,
+) -> Result<Json<TableStatsDelta>, SweepError> {
+    Spi::connect(|client| {
+        let (latest, baseline, elapsed_secs): (TableStats, TableStats, f64) =
+            bracketing_snapshots(&client, &table, &interval)?;
+
+        let sequential_scans = counter_delta(latest.sequential_scans, baseline.sequential_scans);
+        let index_scans = counter_delta(latest.index_scans, baseline.index_scans);
+        let dead_rows_growth = counter_delta(latest.dead_rows, baseline.dead_rows);
+        let heap_blocks_read = counter_delta(latest.heap_blocks_read, baseline.heap_blocks_read);
+        let heap_blocks_hit = counter_delta(latest.heap_blocks_hit, baseline.heap_blocks_hit);
+
+        Ok(Json(TableStatsDelta {
+            elapsed_secs,
+            sequential_scans,
+            index_scans,
+            rows_inserted: counter_delta(latest.rows_inserted, baseline.rows_inserted),
+            rows_updated: counter_delta(latest.rows_updated, baseline.rows_updated),
+            rows_deleted: counter_delta(latest.rows_deleted, baseline.rows_deleted),
+            dead_rows_growth,
+            dead_rows_growth_per_sec: rate(dead_rows_growth, elapsed_secs),
+            index_vs_seqscan_ratio: ratio(index_scans, index_scans + sequential_scans),
+            cache_hit_ratio: ratio(heap_blocks_hit, heap_blocks_hit + heap_blocks_read),
+        }))
+    })
+}
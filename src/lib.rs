@@ -1,198 +1,211 @@
+use error::{get_column, SweepError};
 use pgrx::prelude::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+mod error;
+mod history;
+mod metrics;
+mod vacuum;
+mod worker;
+
 pgrx::pg_module_magic!();
 
-#[derive(Serialize)]
-struct DatabaseStats {
-    timestamp: u64,
-    total_connections: i64,
-    active_connections: i64,
-    idle_connections: i64,
-    total_transactions: i64,
-    commits: i64,
-    rollbacks: i64,
-    blocks_read: i64,
-    blocks_hit: i64,
-    tuples_returned: i64,
-    tuples_fetched: i64,
-    tuples_inserted: i64,
-    tuples_updated: i64,
-    tuples_deleted: i64,
-    temp_files: i64,
-    temp_bytes: i64,
-    deadlocks: i64,
-    block_read_time: f64,
-    block_write_time: f64,
+#[allow(non_snake_case)]
+#[pg_guard]
+pub extern "C" fn _PG_init() {
+    worker::init();
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct DatabaseStats {
+    pub(crate) timestamp: u64,
+    pub(crate) total_connections: i64,
+    pub(crate) active_connections: i64,
+    pub(crate) idle_connections: i64,
+    pub(crate) total_transactions: i64,
+    pub(crate) commits: i64,
+    pub(crate) rollbacks: i64,
+    pub(crate) blocks_read: i64,
+    pub(crate) blocks_hit: i64,
+    pub(crate) tuples_returned: i64,
+    pub(crate) tuples_fetched: i64,
+    pub(crate) tuples_inserted: i64,
+    pub(crate) tuples_updated: i64,
+    pub(crate) tuples_deleted: i64,
+    pub(crate) temp_files: i64,
+    pub(crate) temp_bytes: i64,
+    pub(crate) deadlocks: i64,
+    /// `None` when `track_io_timing` is off, in which case the column is
+    /// unmeasured rather than genuinely zero.
+    pub(crate) block_read_time: Option<f64>,
+    pub(crate) block_write_time: Option<f64>,
+}
+
+const ACTIVITY_QUERY: &str = "SELECT count(*) AS total_connections, \
+         count(*) FILTER (WHERE state = 'active') AS active_connections, \
+         count(*) FILTER (WHERE state = 'idle') AS idle_connections \
+     FROM pg_stat_activity";
+
+const DATABASE_QUERY: &str = "SELECT xact_commit, xact_rollback, blks_read, blks_hit, \
+         tup_returned, tup_fetched, tup_inserted, tup_updated, tup_deleted, \
+         temp_files, temp_bytes, deadlocks, blk_read_time, blk_write_time, \
+         current_setting('track_io_timing')::boolean AS track_io_timing \
+     FROM pg_stat_database \
+     WHERE datname = current_database()";
+
+/// Reads a `DatabaseStats` snapshot without writing it to history, so
+/// read-only callers (e.g. the Prometheus endpoint) don't fill
+/// `pg_sweep_stats_history` at a scrape cadence decoupled from
+/// `pg_sweep.collection_interval`.
+pub(crate) fn query_database_stats(client: &SpiClient<'_>) -> Result<DatabaseStats, SweepError> {
+    // Two round-trips total: one aggregate over pg_stat_activity for the
+    // connection counts, one row from pg_stat_database for every counter.
+    // Pulling everything from a single row each means the fields of a
+    // snapshot are mutually consistent, which matters once deltas are
+    // computed from two snapshots.
+    let mut activity_rows = client.select(ACTIVITY_QUERY, None, None);
+    let activity_row = activity_rows.next().ok_or(SweepError::MissingValue {
+        query: ACTIVITY_QUERY,
+        column: "*",
+    })?;
+
+    let mut database_rows = client.select(DATABASE_QUERY, None, None);
+    let database_row = database_rows.next().ok_or(SweepError::MissingValue {
+        query: DATABASE_QUERY,
+        column: "*",
+    })?;
+
+    let track_io_timing: bool = get_column(&database_row, DATABASE_QUERY, "track_io_timing")?;
+
+    let commits = get_column(&database_row, DATABASE_QUERY, "xact_commit")?;
+    let rollbacks = get_column(&database_row, DATABASE_QUERY, "xact_rollback")?;
+
+    Ok(DatabaseStats {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        total_connections: get_column(&activity_row, ACTIVITY_QUERY, "total_connections")?,
+        active_connections: get_column(&activity_row, ACTIVITY_QUERY, "active_connections")?,
+        idle_connections: get_column(&activity_row, ACTIVITY_QUERY, "idle_connections")?,
+        total_transactions: commits + rollbacks,
+        commits,
+        rollbacks,
+        blocks_read: get_column(&database_row, DATABASE_QUERY, "blks_read")?,
+        blocks_hit: get_column(&database_row, DATABASE_QUERY, "blks_hit")?,
+        tuples_returned: get_column(&database_row, DATABASE_QUERY, "tup_returned")?,
+        tuples_fetched: get_column(&database_row, DATABASE_QUERY, "tup_fetched")?,
+        tuples_inserted: get_column(&database_row, DATABASE_QUERY, "tup_inserted")?,
+        tuples_updated: get_column(&database_row, DATABASE_QUERY, "tup_updated")?,
+        tuples_deleted: get_column(&database_row, DATABASE_QUERY, "tup_deleted")?,
+        temp_files: get_column(&database_row, DATABASE_QUERY, "temp_files")?,
+        temp_bytes: get_column(&database_row, DATABASE_QUERY, "temp_bytes")?,
+        deadlocks: get_column(&database_row, DATABASE_QUERY, "deadlocks")?,
+        block_read_time: track_io_timing
+            .then(|| get_column(&database_row, DATABASE_QUERY, "blk_read_time"))
+            .transpose()?,
+        block_write_time: track_io_timing
+            .then(|| get_column(&database_row, DATABASE_QUERY, "blk_write_time"))
+            .transpose()?,
+    })
 }
 
 #[pg_extern]
-fn collect_database_stats() -> Json<DatabaseStats> {
+fn collect_database_stats() -> Result<Json<DatabaseStats>, SweepError> {
     Spi::connect(|client| {
-        let stats = DatabaseStats {
-            timestamp: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
-            total_connections: query_single_value(
-                client,
-                "SELECT count(*) FROM pg_stat_activity",
-            ),
-            active_connections: query_single_value(
-                client,
-                "SELECT count(*) FROM pg_stat_activity WHERE state = 'active'",
-            ),
-            idle_connections: query_single_value(
-                client,
-                "SELECT count(*) FROM pg_stat_activity WHERE state = 'idle'",
-            ),
-            total_transactions: query_single_value(
-                client,
-                "SELECT xact_commit + xact_rollback FROM pg_stat_database WHERE datname = current_database()",
-            ),
-            commits: query_single_value(
-                client,
-                "SELECT xact_commit FROM pg_stat_database WHERE datname = current_database()",
-            ),
-            rollbacks: query_single_value(
-                client,
-                "SELECT xact_rollback FROM pg_stat_database WHERE datname = current_database()",
-            ),
-            blocks_read: query_single_value(
-                client,
-                "SELECT blks_read FROM pg_stat_database WHERE datname = current_database()",
-            ),
-            blocks_hit: query_single_value(
-                client,
-                "SELECT blks_hit FROM pg_stat_database WHERE datname = current_database()",
-            ),
-            tuples_returned: query_single_value(
-                client,
-                "SELECT tup_returned FROM pg_stat_database WHERE datname = current_database()",
-            ),
-            tuples_fetched: query_single_value(
-                client,
-                "SELECT tup_fetched FROM pg_stat_database WHERE datname = current_database()",
-            ),
-            tuples_inserted: query_single_value(
-                client,
-                "SELECT tup_inserted FROM pg_stat_database WHERE datname = current_database()",
-            ),
-            tuples_updated: query_single_value(
-                client,
-                "SELECT tup_updated FROM pg_stat_database WHERE datname = current_database()",
-            ),
-            tuples_deleted: query_single_value(
-                client,
-                "SELECT tup_deleted FROM pg_stat_database WHERE datname = current_database()",
-            ),
-            temp_files: query_single_value(
-                client,
-                "SELECT temp_files FROM pg_stat_database WHERE datname = current_database()",
-            ),
-            temp_bytes: query_single_value(
-                client,
-                "SELECT temp_bytes FROM pg_stat_database WHERE datname = current_database()",
-            ),
-            deadlocks: query_single_value(
-                client,
-                "SELECT deadlocks FROM pg_stat_database WHERE datname = current_database()",
-            ),
-            block_read_time: query_single_value_float(
-                client,
-                "SELECT blk_read_time FROM pg_stat_database WHERE datname = current_database()",
-            ),
-            block_write_time: query_single_value_float(
-                client,
-                "SELECT blk_write_time FROM pg_stat_database WHERE datname = current_database()",
-            ),
-        };
-        
+        let stats = query_database_stats(&client)?;
+        history::record_snapshot(&client, history::Target::Database, &stats);
         Ok(Json(stats))
     })
-    .unwrap()
 }
 
-fn query_single_value(client: &mut Spi, query: &str) -> i64 {
-    client
-        .select(query, None, None)
-        .first()
-        .get_one::<i64>()
-        .unwrap()
-        .unwrap_or(0)
-}
+const TABLE_QUERY: &str = "SELECT schemaname, relname, \
+         seq_scan, seq_tup_read, \
+         idx_scan, idx_tup_fetch, \
+         n_tup_ins, n_tup_upd, n_tup_del, \
+         n_live_tup, n_dead_tup, \
+         heap_blks_read, heap_blks_hit, \
+         idx_blks_read, idx_blks_hit, \
+         current_setting('track_counts')::boolean AS track_counts \
+     FROM pg_stat_user_tables";
+
+/// Reads a `TableStats` snapshot per user table without writing it to
+/// history; see [`query_database_stats`] for why read-only callers need this.
+pub(crate) fn query_table_stats(
+    client: &SpiClient<'_>,
+) -> Result<HashMap<String, TableStats>, SweepError> {
+    let mut table_stats = HashMap::new();
+    let mut results = client.select(TABLE_QUERY, None, None).peekable();
+
+    // track_counts is a per-session setting, so it reads the same on
+    // every row; one peek at the first row is enough to catch it being
+    // off without a separate round-trip, and without erroring on a
+    // database that legitimately has zero user tables.
+    if let Some(first_row) = results.peek() {
+        let track_counts: bool = get_column(first_row, TABLE_QUERY, "track_counts")?;
+        if !track_counts {
+            return Err(SweepError::TrackingDisabled {
+                guc: "track_counts",
+                column: "*",
+            });
+        }
+    }
 
-fn query_single_value_float(client: &mut Spi, query: &str) -> f64 {
-    client
-        .select(query, None, None)
-        .first()
-        .get_one::<f64>()
-        .unwrap()
-        .unwrap_or(0.0)
+    for row in results {
+        let schema: String = get_column(&row, TABLE_QUERY, "schemaname")?;
+        let table: String = get_column(&row, TABLE_QUERY, "relname")?;
+        let key = format!("{}.{}", schema, table);
+
+        let stats = TableStats {
+            sequential_scans: get_column(&row, TABLE_QUERY, "seq_scan")?,
+            sequential_rows_read: get_column(&row, TABLE_QUERY, "seq_tup_read")?,
+            index_scans: get_column(&row, TABLE_QUERY, "idx_scan")?,
+            index_rows_fetched: get_column(&row, TABLE_QUERY, "idx_tup_fetch")?,
+            rows_inserted: get_column(&row, TABLE_QUERY, "n_tup_ins")?,
+            rows_updated: get_column(&row, TABLE_QUERY, "n_tup_upd")?,
+            rows_deleted: get_column(&row, TABLE_QUERY, "n_tup_del")?,
+            live_rows: get_column(&row, TABLE_QUERY, "n_live_tup")?,
+            dead_rows: get_column(&row, TABLE_QUERY, "n_dead_tup")?,
+            heap_blocks_read: get_column(&row, TABLE_QUERY, "heap_blks_read")?,
+            heap_blocks_hit: get_column(&row, TABLE_QUERY, "heap_blks_hit")?,
+            index_blocks_read: get_column(&row, TABLE_QUERY, "idx_blks_read")?,
+            index_blocks_hit: get_column(&row, TABLE_QUERY, "idx_blks_hit")?,
+        };
+
+        table_stats.insert(key, stats);
+    }
+
+    Ok(table_stats)
 }
 
 #[pg_extern]
-fn collect_table_stats() -> Json<HashMap<String, TableStats>> {
+fn collect_table_stats() -> Result<Json<HashMap<String, TableStats>>, SweepError> {
     Spi::connect(|client| {
-        let mut table_stats = HashMap::new();
-        
-        let results = client.select(
-            "SELECT schemaname, relname, 
-                    seq_scan, seq_tup_read, 
-                    idx_scan, idx_tup_fetch,
-                    n_tup_ins, n_tup_upd, n_tup_del,
-                    n_live_tup, n_dead_tup,
-                    heap_blks_read, heap_blks_hit,
-                    idx_blks_read, idx_blks_hit
-             FROM pg_stat_user_tables",
-            None,
-            None,
-        );
-
-        for row in results {
-            let schema: String = row.get_by_name("schemaname").unwrap().unwrap();
-            let table: String = row.get_by_name("relname").unwrap().unwrap();
-            let key = format!("{}.{}", schema, table);
-            
-            table_stats.insert(key, TableStats {
-                sequential_scans: row.get_by_name("seq_scan").unwrap().unwrap(),
-                sequential_rows_read: row.get_by_name("seq_tup_read").unwrap().unwrap(),
-                index_scans: row.get_by_name("idx_scan").unwrap().unwrap(),
-                index_rows_fetched: row.get_by_name("idx_tup_fetch").unwrap().unwrap(),
-                rows_inserted: row.get_by_name("n_tup_ins").unwrap().unwrap(),
-                rows_updated: row.get_by_name("n_tup_upd").unwrap().unwrap(),
-                rows_deleted: row.get_by_name("n_tup_del").unwrap().unwrap(),
-                live_rows: row.get_by_name("n_live_tup").unwrap().unwrap(),
-                dead_rows: row.get_by_name("n_dead_tup").unwrap().unwrap(),
-                heap_blocks_read: row.get_by_name("heap_blks_read").unwrap().unwrap(),
-                heap_blocks_hit: row.get_by_name("heap_blks_hit").unwrap().unwrap(),
-                index_blocks_read: row.get_by_name("idx_blks_read").unwrap().unwrap(),
-                index_blocks_hit: row.get_by_name("idx_blks_hit").unwrap().unwrap(),
-            });
+        let table_stats = query_table_stats(&client)?;
+        for (key, stats) in &table_stats {
+            history::record_snapshot(&client, history::Target::Table(key), stats);
         }
-        
         Ok(Json(table_stats))
     })
-    .unwrap()
 }
 
-#[derive(Serialize)]
-struct TableStats {
-    sequential_scans: i64,
-    sequential_rows_read: i64,
-    index_scans: i64,
-    index_rows_fetched: i64,
-    rows_inserted: i64,
-    rows_updated: i64,
-    rows_deleted: i64,
-    live_rows: i64,
-    dead_rows: i64,
-    heap_blocks_read: i64,
-    heap_blocks_hit: i64,
-    index_blocks_read: i64,
-    index_blocks_hit: i64,
+#[derive(Serialize, Deserialize)]
+pub(crate) struct TableStats {
+    pub(crate) sequential_scans: i64,
+    pub(crate) sequential_rows_read: i64,
+    pub(crate) index_scans: i64,
+    pub(crate) index_rows_fetched: i64,
+    pub(crate) rows_inserted: i64,
+    pub(crate) rows_updated: i64,
+    pub(crate) rows_deleted: i64,
+    pub(crate) live_rows: i64,
+    pub(crate) dead_rows: i64,
+    pub(crate) heap_blocks_read: i64,
+    pub(crate) heap_blocks_hit: i64,
+    pub(crate) index_blocks_read: i64,
+    pub(crate) index_blocks_hit: i64,
 }
 
 #[cfg(any(test, feature = "pg_test"))]
@@ -207,7 +220,7 @@ mod tests {
 
     #[pg_test]
     fn test_collect_database_stats() {
-        let stats = crate::collect_database_stats();
+        let stats = crate::collect_database_stats().unwrap();
         assert!(stats.0.total_connections >= 0);
     }
 }
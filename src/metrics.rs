@@ -0,0 +1,178 @@
+//! Prometheus text-format exposition.
+
+use crate::error::SweepError;
+use crate::{query_database_stats, query_table_stats, DatabaseStats, TableStats};
+use pgrx::prelude::*;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+#[pg_extern]
+fn prometheus_metrics() -> Result<String, SweepError> {
+    // Reads snapshots directly rather than going through
+    // `collect_database_stats`/`collect_table_stats`, since a scraper polls
+    // at its own cadence and shouldn't also write a row into
+    // `pg_sweep_stats_history` on every scrape.
+    Spi::connect(|client| {
+        let database = query_database_stats(&client)?;
+        let tables = query_table_stats(&client)?;
+
+        let mut out = String::new();
+        render_database_metrics(&mut out, &database);
+        render_table_metrics(&mut out, &tables);
+        Ok(out)
+    })
+}
+
+fn render_database_metrics(out: &mut String, stats: &DatabaseStats) {
+    gauge(out, "pg_sweep_total_connections", "Current backend connections.", stats.total_connections as f64);
+    gauge(out, "pg_sweep_active_connections", "Backends currently running a query.", stats.active_connections as f64);
+    gauge(out, "pg_sweep_idle_connections", "Backends currently idle.", stats.idle_connections as f64);
+    counter(out, "pg_sweep_commits_total", "Committed transactions.", stats.commits);
+    counter(out, "pg_sweep_rollbacks_total", "Rolled back transactions.", stats.rollbacks);
+    counter(out, "pg_sweep_blocks_read_total", "Disk blocks read.", stats.blocks_read);
+    counter(out, "pg_sweep_blocks_hit_total", "Buffer cache hits.", stats.blocks_hit);
+    counter(out, "pg_sweep_tuples_returned_total", "Rows scanned by sequential and index scans.", stats.tuples_returned);
+    counter(out, "pg_sweep_tuples_fetched_total", "Rows fetched by index scans.", stats.tuples_fetched);
+    counter(out, "pg_sweep_tuples_inserted_total", "Rows inserted.", stats.tuples_inserted);
+    counter(out, "pg_sweep_tuples_updated_total", "Rows updated.", stats.tuples_updated);
+    counter(out, "pg_sweep_tuples_deleted_total", "Rows deleted.", stats.tuples_deleted);
+    counter(out, "pg_sweep_temp_files_total", "Temporary files created.", stats.temp_files);
+    counter(out, "pg_sweep_temp_bytes_total", "Temporary file bytes written.", stats.temp_bytes);
+    counter(out, "pg_sweep_deadlocks_total", "Deadlocks detected.", stats.deadlocks);
+
+    // Omitted entirely when track_io_timing is off, rather than reported as
+    // a misleading zero.
+    if let Some(block_read_time) = stats.block_read_time {
+        counter_f64(
+            out,
+            "pg_sweep_block_read_time_ms_total",
+            "Total time spent reading blocks, in milliseconds.",
+            block_read_time,
+        );
+    }
+    if let Some(block_write_time) = stats.block_write_time {
+        counter_f64(
+            out,
+            "pg_sweep_block_write_time_ms_total",
+            "Total time spent writing blocks, in milliseconds.",
+            block_write_time,
+        );
+    }
+}
+
+fn render_table_metrics(out: &mut String, tables: &HashMap<String, TableStats>) {
+    table_counter(out, tables, "pg_sweep_table_sequential_scans_total", "Sequential scans.", |s| s.sequential_scans);
+    table_counter(out, tables, "pg_sweep_table_index_scans_total", "Index scans.", |s| s.index_scans);
+    table_counter(out, tables, "pg_sweep_table_rows_inserted_total", "Rows inserted.", |s| s.rows_inserted);
+    table_counter(out, tables, "pg_sweep_table_rows_updated_total", "Rows updated.", |s| s.rows_updated);
+    table_counter(out, tables, "pg_sweep_table_rows_deleted_total", "Rows deleted.", |s| s.rows_deleted);
+    table_gauge(out, tables, "pg_sweep_table_live_rows", "Estimated live rows.", |s| s.live_rows as f64);
+    table_gauge(out, tables, "pg_sweep_table_dead_rows", "Estimated dead rows awaiting vacuum.", |s| s.dead_rows as f64);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: i64) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} counter").unwrap();
+    writeln!(out, "{name} {value}").unwrap();
+}
+
+fn counter_f64(out: &mut String, name: &str, help: &str, value: f64) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} counter").unwrap();
+    writeln!(out, "{name} {value}").unwrap();
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} gauge").unwrap();
+    writeln!(out, "{name} {value}").unwrap();
+}
+
+fn table_counter(
+    out: &mut String,
+    tables: &HashMap<String, TableStats>,
+    name: &str,
+    help: &str,
+    value: impl Fn(&TableStats) -> i64,
+) {
+    table_metric(out, tables, name, help, "counter", |s| value(s) as f64);
+}
+
+fn table_gauge(
+    out: &mut String,
+    tables: &HashMap<String, TableStats>,
+    name: &str,
+    help: &str,
+    value: impl Fn(&TableStats) -> f64,
+) {
+    table_metric(out, tables, name, help, "gauge", value);
+}
+
+fn table_metric(
+    out: &mut String,
+    tables: &HashMap<String, TableStats>,
+    name: &str,
+    help: &str,
+    metric_type: &str,
+    value: impl Fn(&TableStats) -> f64,
+) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} {metric_type}").unwrap();
+    for (key, stats) in tables {
+        let (schema, table) = split_schema_table(key);
+        writeln!(
+            out,
+            "{name}{{schema=\"{}\",table=\"{}\"}} {}",
+            escape_label(schema),
+            escape_label(table),
+            value(stats)
+        )
+        .unwrap();
+    }
+}
+
+fn split_schema_table(key: &str) -> (&str, &str) {
+    key.split_once('.').unwrap_or((key, ""))
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_label, split_schema_table};
+
+    #[test]
+    fn escape_label_backslash() {
+        assert_eq!(escape_label(r"a\b"), r"a\\b");
+    }
+
+    #[test]
+    fn escape_label_quote() {
+        assert_eq!(escape_label(r#"a"b"#), r#"a\"b"#);
+    }
+
+    #[test]
+    fn escape_label_newline() {
+        assert_eq!(escape_label("a\nb"), "a\\nb");
+    }
+
+    #[test]
+    fn escape_label_plain_is_unchanged() {
+        assert_eq!(escape_label("plain"), "plain");
+    }
+
+    #[test]
+    fn split_schema_table_with_schema() {
+        assert_eq!(split_schema_table("public.accounts"), ("public", "accounts"));
+    }
+
+    #[test]
+    fn split_schema_table_without_schema() {
+        assert_eq!(split_schema_table("accounts"), ("accounts", ""));
+    }
+}
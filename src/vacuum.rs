@@ -0,0 +1,144 @@
+//! Dead-tuple / bloat advisory built on top of `pg_stat_user_tables`.
+
+use crate::error::{get_column, get_optional_column, SweepError};
+use pgrx::prelude::*;
+
+const QUERY: &str = "SELECT \
+         s.schemaname, \
+         s.relname, \
+         s.n_live_tup, \
+         s.n_dead_tup, \
+         COALESCE(s.n_dead_tup::float8 / NULLIF(s.n_live_tup + s.n_dead_tup, 0), 0) AS dead_tuple_ratio, \
+         EXTRACT(EPOCH FROM (now() - s.last_autovacuum)) AS seconds_since_last_autovacuum, \
+         EXTRACT(EPOCH FROM (now() - s.last_autoanalyze)) AS seconds_since_last_autoanalyze, \
+         s.last_autovacuum IS NULL AS never_autovacuumed, \
+         s.n_dead_tup > ( \
+             COALESCE( \
+                 (SELECT option_value::int FROM pg_options_to_table(c.reloptions) \
+                  WHERE option_name = 'autovacuum_vacuum_threshold'), \
+                 current_setting('autovacuum_vacuum_threshold')::int \
+             ) \
+             + COALESCE( \
+                 (SELECT option_value::float8 FROM pg_options_to_table(c.reloptions) \
+                  WHERE option_name = 'autovacuum_vacuum_scale_factor'), \
+                 current_setting('autovacuum_vacuum_scale_factor')::float8 \
+             ) * s.n_live_tup \
+         ) AS over_threshold, \
+         current_setting('track_counts')::boolean AS track_counts \
+     FROM pg_stat_user_tables s \
+     JOIN pg_class c ON c.oid = s.relid";
+
+/// Dead tuple ratios at or above this are flagged regardless of whether
+/// autovacuum's own threshold has technically been crossed yet.
+const HIGH_DEAD_RATIO: f64 = 0.2;
+
+#[derive(serde::Serialize)]
+pub(crate) struct VacuumCandidate {
+    schema: String,
+    table: String,
+    live_rows: i64,
+    dead_rows: i64,
+    dead_tuple_ratio: f64,
+    seconds_since_last_autovacuum: Option<f64>,
+    seconds_since_last_autoanalyze: Option<f64>,
+    over_threshold: bool,
+    urgency_score: f64,
+    reasons: Vec<&'static str>,
+}
+
+fn urgency_score(dead_tuple_ratio: f64, over_threshold: bool, never_autovacuumed: bool) -> f64 {
+    dead_tuple_ratio * 100.0
+        + if over_threshold { 50.0 } else { 0.0 }
+        + if never_autovacuumed { 25.0 } else { 0.0 }
+}
+
+#[pg_extern]
+fn vacuum_candidates() -> Result<Json<Vec<VacuumCandidate>>, SweepError> {
+    Spi::connect(|client| {
+        let mut candidates = Vec::new();
+        let mut results = client.select(QUERY, None, None).peekable();
+
+        // track_counts is a per-session setting, so it reads the same on
+        // every row; one peek at the first row is enough to catch it being
+        // off without a separate round-trip, and without erroring on a
+        // database that legitimately has zero user tables.
+        if let Some(first_row) = results.peek() {
+            let track_counts: bool = get_column(first_row, QUERY, "track_counts")?;
+            if !track_counts {
+                return Err(SweepError::TrackingDisabled {
+                    guc: "track_counts",
+                    column: "*",
+                });
+            }
+        }
+
+        for row in results {
+            let dead_tuple_ratio: f64 = get_column(&row, QUERY, "dead_tuple_ratio")?;
+            let never_autovacuumed: bool = get_column(&row, QUERY, "never_autovacuumed")?;
+            let over_threshold: bool = get_column(&row, QUERY, "over_threshold")?;
+
+            let mut reasons = Vec::new();
+            if dead_tuple_ratio >= HIGH_DEAD_RATIO {
+                reasons.push("high dead tuple ratio");
+            }
+            if never_autovacuumed {
+                reasons.push("never autovacuumed");
+            }
+            if over_threshold {
+                reasons.push("exceeds autovacuum_vacuum_threshold/scale_factor");
+            }
+
+            let urgency_score = urgency_score(dead_tuple_ratio, over_threshold, never_autovacuumed);
+
+            candidates.push(VacuumCandidate {
+                schema: get_column(&row, QUERY, "schemaname")?,
+                table: get_column(&row, QUERY, "relname")?,
+                live_rows: get_column(&row, QUERY, "n_live_tup")?,
+                dead_rows: get_column(&row, QUERY, "n_dead_tup")?,
+                dead_tuple_ratio,
+                seconds_since_last_autovacuum: get_optional_column(
+                    &row,
+                    QUERY,
+                    "seconds_since_last_autovacuum",
+                )?,
+                seconds_since_last_autoanalyze: get_optional_column(
+                    &row,
+                    QUERY,
+                    "seconds_since_last_autoanalyze",
+                )?,
+                over_threshold,
+                urgency_score,
+                reasons,
+            });
+        }
+
+        candidates.sort_by(|a, b| b.urgency_score.total_cmp(&a.urgency_score));
+
+        Ok(Json(candidates))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{urgency_score, HIGH_DEAD_RATIO};
+
+    #[test]
+    fn urgency_score_healthy_table_is_zero() {
+        assert_eq!(urgency_score(0.0, false, false), 0.0);
+    }
+
+    #[test]
+    fn urgency_score_combines_all_factors() {
+        assert_eq!(urgency_score(0.2, true, true), 20.0 + 50.0 + 25.0);
+    }
+
+    #[test]
+    fn urgency_score_scales_with_dead_tuple_ratio() {
+        assert_eq!(urgency_score(0.5, false, false), 50.0);
+    }
+
+    #[test]
+    fn high_dead_ratio_cutoff_is_twenty_percent() {
+        assert_eq!(HIGH_DEAD_RATIO, 0.2);
+    }
+}
@@ -0,0 +1,112 @@
+//! Background worker that periodically collects and persists stats snapshots.
+
+use crate::error::{track_enabled, SweepError};
+use crate::{collect_database_stats, collect_table_stats};
+use pgrx::bgworkers::{BackgroundWorker, BackgroundWorkerBuilder, BgWorkerStartTime, SignalWakeFlags};
+use pgrx::prelude::*;
+use pgrx::{GucContext, GucFlags, GucRegistry, GucSetting};
+use std::ffi::CStr;
+use std::time::Duration;
+
+static COLLECTION_INTERVAL_SECS: GucSetting<i32> = GucSetting::<i32>::new(60);
+static RETENTION_DAYS: GucSetting<i32> = GucSetting::<i32>::new(7);
+static TARGET_DATABASE: GucSetting<Option<&'static CStr>> = GucSetting::<Option<&'static CStr>>::new(None);
+
+pub(crate) fn init() {
+    GucRegistry::define_int_guc(
+        "pg_sweep.collection_interval",
+        "How often, in seconds, the pg_sweep background worker takes a snapshot.",
+        "Lower values track trends more closely at the cost of more frequent catalog scans.",
+        &COLLECTION_INTERVAL_SECS,
+        1,
+        3600,
+        GucContext::Sighup,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_int_guc(
+        "pg_sweep.retention_days",
+        "How many days of snapshot history the pg_sweep background worker keeps.",
+        "Snapshots older than this are pruned on every collection tick.",
+        &RETENTION_DAYS,
+        1,
+        3650,
+        GucContext::Sighup,
+        GucFlags::default(),
+    );
+
+    // The worker is registered once for the whole cluster, so it can't infer
+    // which database pg_sweep was installed into; it must be told.
+    GucRegistry::define_string_guc(
+        "pg_sweep.database",
+        "Which database the pg_sweep background worker connects to and collects snapshots from.",
+        "Set this to the database pg_sweep was CREATE EXTENSION'd into. A server restart is required to pick up a new value.",
+        &TARGET_DATABASE,
+        GucContext::Postmaster,
+        GucFlags::default(),
+    );
+
+    BackgroundWorkerBuilder::new("pg_sweep collector")
+        .set_function("pg_sweep_collector_main")
+        .set_library("pg_sweep")
+        .set_start_time(BgWorkerStartTime::RecoveryFinished)
+        .enable_spi_access()
+        .load();
+}
+
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn pg_sweep_collector_main(_arg: pg_sys::Datum) {
+    BackgroundWorker::attach_signal_handlers(SignalWakeFlags::SIGHUP | SignalWakeFlags::SIGTERM);
+
+    let Some(database) = TARGET_DATABASE.get() else {
+        warning!("pg_sweep collector: `pg_sweep.database` is not set, exiting without collecting snapshots");
+        return;
+    };
+    let database = database
+        .to_str()
+        .expect("pg_sweep.database must be valid UTF-8");
+
+    BackgroundWorker::connect_worker_to_spi(Some(database), None);
+
+    while BackgroundWorker::wait_latch(Some(Duration::from_secs(
+        COLLECTION_INTERVAL_SECS.get().max(1) as u64,
+    ))) {
+        if BackgroundWorker::sigterm_received() {
+            break;
+        }
+
+        BackgroundWorker::transaction(|| {
+            match Spi::connect(|client| track_enabled(&client, "track_counts")) {
+                Ok(true) => {
+                    if let Err(e) = collect_database_stats() {
+                        warning!("pg_sweep collector: {e}");
+                    }
+                    if let Err(e) = collect_table_stats() {
+                        warning!("pg_sweep collector: {e}");
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => warning!("pg_sweep collector: {e}"),
+            }
+
+            if let Err(e) = prune_history() {
+                warning!("pg_sweep collector: {e}");
+            }
+        });
+    }
+}
+
+fn prune_history() -> Result<(), SweepError> {
+    const QUERY: &str = "DELETE FROM pg_sweep_stats_history WHERE captured_at < now() - interval '<n> days'";
+
+    Spi::run(&format!(
+        "DELETE FROM pg_sweep_stats_history WHERE captured_at < now() - interval '{} days'",
+        RETENTION_DAYS.get()
+    ))
+    .map_err(|source| SweepError::Query {
+        query: QUERY,
+        column: "*",
+        source,
+    })
+}